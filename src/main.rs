@@ -1,7 +1,16 @@
-use std::{fmt::Display, fs::OpenOptions, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    sync::mpsc::{sync_channel, SyncSender},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use clap::{Parser, Subcommand};
-use rand::{seq::SliceRandom, Rng};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use skillratings::{
     glicko::{decay_deviation, glicko, GlickoConfig, GlickoRating},
@@ -13,10 +22,38 @@ struct Args {
     #[command(subcommand)]
     command: Commands,
 
-    /// Path to the CSV file to process.
+    /// Path to the ratings file to process.
     /// This file will be read, resorted, and then rewritten.
+    /// A `.jsonl` extension reads and writes newline-delimited JSON, and a `.bin`
+    /// extension reads and writes the binary format, instead of CSV.
     #[arg(short = 'f', long, default_value = "items.csv")]
     file: PathBuf,
+
+    /// Storage format to use, overriding the format normally inferred from `file`'s extension.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+}
+
+/// The on-disk encoding of the ratings file. Selected by `--format`, or else inferred
+/// from the file extension (`.jsonl` -> Jsonl, `.bin` -> Binary, anything else -> Csv).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Format {
+    Csv,
+    Jsonl,
+    Binary,
+}
+
+impl Format {
+    fn resolve(file: &PathBuf, format: Option<Format>) -> Format {
+        if let Some(format) = format {
+            return format;
+        }
+        match file.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") => Format::Jsonl,
+            Some("bin") => Format::Binary,
+            _ => Format::Csv,
+        }
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -33,55 +70,401 @@ enum Commands {
         /// This will ask you additional questions about items you have already sorted.
         #[arg(short, long)]
         decay: bool,
+
+        /// Seed the random number generator for reproducible resorting sessions.
+        /// If absent, randomness is pulled from OS entropy as usual.
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+
+    /// Rebuild the ratings file from scratch by replaying the comparison journal.
+    /// Useful after tweaking Glicko parameters or fixing a misclick, since it
+    /// re-derives every rating from the raw recorded judgments instead of the cache.
+    Replay,
+
+    /// Export the current ratings as newline-delimited JSON, one RatedItem per line.
+    Export {
+        /// Path to write the JSONL export to.
+        output: PathBuf,
     },
 }
 
 fn main() {
     let args = Args::parse();
+    let format = Format::resolve(&args.file, args.format);
     match args.command {
-        Commands::Add { name } => add_row_to_file(name, &args.file),
-        Commands::Resort { decay } => run_resort(&args.file, decay),
+        Commands::Add { name } => add_row_to_file(name, &args.file, format),
+        Commands::Resort { decay, seed } => run_resort(&args.file, decay, seed, format),
+        Commands::Replay => run_replay(&args.file, format),
+        Commands::Export { output } => run_export(&args.file, &output, format),
+    }
+}
+
+fn load_items(file: &PathBuf, format: Format) -> Vec<RatedItem> {
+    match format {
+        Format::Csv => load_items_csv(file),
+        Format::Jsonl => load_items_jsonl(file),
+        Format::Binary => load_items_binary(file),
     }
 }
 
-fn add_row_to_file(row: String, file: &PathBuf) {
+fn load_items_csv(file: &PathBuf) -> Vec<RatedItem> {
+    let opened = OpenOptions::new()
+        .read(true)
+        .open(file)
+        .expect(&format!("Failed to open file {}", file.display()));
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(opened);
+    let mut items = vec![];
+    for record in reader.records() {
+        let record = record.expect("Failed to read record");
+        let item: RatedItem = record.deserialize(None).expect("Failed to parse record");
+        items.push(item);
+    }
+    items
+}
+
+fn load_items_jsonl(file: &PathBuf) -> Vec<RatedItem> {
+    let opened = OpenOptions::new()
+        .read(true)
+        .open(file)
+        .expect(&format!("Failed to open file {}", file.display()));
+    BufReader::new(opened)
+        .lines()
+        .filter_map(|line| {
+            let line = line.expect("Failed to read line");
+            if line.trim().is_empty() {
+                None
+            } else {
+                let record: RatedItemRecord =
+                    serde_json::from_str(&line).expect("Failed to parse JSONL record");
+                Some(record.into())
+            }
+        })
+        .collect()
+}
+
+fn write_items_csv(file: &PathBuf, items: &[RatedItem]) {
     let opened = OpenOptions::new()
         .create(true)
-        .append(true)
+        .truncate(true)
+        .write(true)
         .open(file)
         .expect(&format!("Failed to open file {}", file.display()));
     let mut csv_writer = csv::WriterBuilder::new()
         .has_headers(false)
         .from_writer(opened);
-    csv_writer
-        .write_record(&[&row, "1500.0", "100.0", "0"])
-        .expect("Failed to write new row to file");
-    println!("Added new record: {row:?}");
+    for item in items {
+        csv_writer.serialize(item).expect("Failed to write record");
+    }
 }
 
-#[derive(Deserialize, Serialize)]
-struct RatedItem {
-    name: String,
-    rating: f64,
-    deviation: f64,
-    rating_quartile: i64,
+fn write_items_jsonl(file: &PathBuf, items: &[RatedItem]) {
+    let opened = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(file)
+        .expect(&format!("Failed to open file {}", file.display()));
+    let mut writer = opened;
+    for item in items {
+        let line = serde_json::to_string(item).expect("Failed to serialize record");
+        writeln!(writer, "{line}").expect("Failed to write JSONL record");
+    }
 }
 
-fn run_resort(file: &PathBuf, do_decay: bool) {
-    println!("Loading ratings from disk...");
+/// Binary format: a stream of records, each a little-endian `u32` byte length
+/// followed by that many bytes of `bincode`-encoded `RatedItem`. This keeps
+/// `rating`/`deviation` bit-exact across save/load and skips the text parsing
+/// that CSV and JSON incur on every round-trip.
+fn load_items_binary(file: &PathBuf) -> Vec<RatedItem> {
     let opened = OpenOptions::new()
         .read(true)
         .open(file)
         .expect(&format!("Failed to open file {}", file.display()));
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(opened);
+    let mut reader = BufReader::new(opened);
     let mut items = vec![];
-    for record in reader.records() {
-        let record = record.expect("Failed to read record");
-        let item: RatedItem = record.deserialize(None).expect("Failed to parse record");
+    loop {
+        let mut len_bytes = [0u8; 4];
+        match reader.read_exact(&mut len_bytes) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => panic!("Failed to read record length: {err}"),
+        }
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        let mut record_bytes = vec![0u8; len];
+        reader
+            .read_exact(&mut record_bytes)
+            .expect("Failed to read record bytes");
+        let item: RatedItem =
+            bincode::deserialize(&record_bytes).expect("Failed to decode binary record");
         items.push(item);
     }
+    items
+}
+
+fn write_items_binary(file: &PathBuf, items: &[RatedItem]) {
+    let opened = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(file)
+        .expect(&format!("Failed to open file {}", file.display()));
+    let mut writer = opened;
+    for item in items {
+        let record_bytes = bincode::serialize(item).expect("Failed to encode binary record");
+        writer
+            .write_all(&(record_bytes.len() as u32).to_le_bytes())
+            .expect("Failed to write record length");
+        writer
+            .write_all(&record_bytes)
+            .expect("Failed to write binary record");
+    }
+}
+
+fn run_export(file: &PathBuf, output: &PathBuf, format: Format) {
+    println!("Loading ratings from disk...");
+    let items = load_items(file, format);
+    write_items_jsonl(output, &items);
+    println!("Exported {} items to {}", items.len(), output.display());
+}
+
+/// Path of the append-only comparison journal kept alongside the ratings file.
+fn journal_path(file: &PathBuf) -> PathBuf {
+    file.with_extension("journal.jsonl")
+}
+
+/// A single raw human judgment (or decay step), appended to the journal as it
+/// happens so the derived ratings can be rebuilt from it later.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JournalEntry {
+    Comparison {
+        timestamp: u64,
+        left: String,
+        right: String,
+        outcome: JournalOutcome,
+    },
+    Decay {
+        timestamp: u64,
+        name: String,
+    },
+}
+
+/// Serializable mirror of `skillratings::Outcomes`, which doesn't implement serde traits.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum JournalOutcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+impl From<Outcomes> for JournalOutcome {
+    fn from(outcome: Outcomes) -> Self {
+        match outcome {
+            Outcomes::WIN => JournalOutcome::Win,
+            Outcomes::LOSS => JournalOutcome::Loss,
+            Outcomes::DRAW => JournalOutcome::Draw,
+        }
+    }
+}
+
+impl From<JournalOutcome> for Outcomes {
+    fn from(outcome: JournalOutcome) -> Self {
+        match outcome {
+            JournalOutcome::Win => Outcomes::WIN,
+            JournalOutcome::Loss => Outcomes::LOSS,
+            JournalOutcome::Draw => Outcomes::DRAW,
+        }
+    }
+}
+
+fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is before the UNIX epoch")
+        .as_nanos() as u64
+}
+
+fn append_journal_entry(file: &PathBuf, entry: &JournalEntry) {
+    let path = journal_path(file);
+    let opened = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .expect(&format!("Failed to open journal file {}", path.display()));
+    let mut writer = opened;
+    let line = serde_json::to_string(entry).expect("Failed to serialize journal entry");
+    writeln!(writer, "{line}").expect("Failed to append to journal file");
+}
+
+fn add_row_to_file(row: String, file: &PathBuf, format: Format) {
+    let item = RatedItem {
+        name: row.clone(),
+        rating: default_rating(),
+        deviation: default_deviation(),
+        rating_quartile: default_rating_quartile(),
+    };
+    match format {
+        Format::Jsonl => {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .expect(&format!("Failed to open file {}", file.display()));
+            let mut writer = opened;
+            let line = serde_json::to_string(&item).expect("Failed to serialize new row");
+            writeln!(writer, "{line}").expect("Failed to write new row to file");
+        }
+        Format::Binary => {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .expect(&format!("Failed to open file {}", file.display()));
+            let mut writer = opened;
+            let record_bytes = bincode::serialize(&item).expect("Failed to encode new row");
+            writer
+                .write_all(&(record_bytes.len() as u32).to_le_bytes())
+                .expect("Failed to write new row to file");
+            writer
+                .write_all(&record_bytes)
+                .expect("Failed to write new row to file");
+        }
+        Format::Csv => {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .expect(&format!("Failed to open file {}", file.display()));
+            let mut csv_writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(opened);
+            csv_writer
+                .serialize(&item)
+                .expect("Failed to write new row to file");
+        }
+    }
+    println!("Added new record: {row:?}");
+}
+
+fn default_rating() -> f64 {
+    1500.0
+}
+
+fn default_deviation() -> f64 {
+    100.0
+}
+
+fn default_rating_quartile() -> i64 {
+    0
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RatedItem {
+    name: String,
+    #[serde(default = "default_rating")]
+    rating: f64,
+    #[serde(default = "default_deviation")]
+    deviation: f64,
+    #[serde(default = "default_rating_quartile")]
+    rating_quartile: i64,
+}
+
+/// JSONL wire representation of a `RatedItem`, used only when reading: unlike
+/// CSV (always positional, every column present) and the binary format (fixed-width,
+/// not self-describing), JSON can spell a field out as `null`. `Option` lets that
+/// round-trip to the same default as an altogether-missing key, instead of the
+/// type error a bare `f64`/`i64` field with only `#[serde(default)]` would give.
+#[derive(Debug, Deserialize)]
+struct RatedItemRecord {
+    name: String,
+    #[serde(default)]
+    rating: Option<f64>,
+    #[serde(default)]
+    deviation: Option<f64>,
+    #[serde(default)]
+    rating_quartile: Option<i64>,
+}
+
+impl From<RatedItemRecord> for RatedItem {
+    fn from(record: RatedItemRecord) -> Self {
+        RatedItem {
+            name: record.name,
+            rating: record.rating.unwrap_or_else(default_rating),
+            deviation: record.deviation.unwrap_or_else(default_deviation),
+            rating_quartile: record
+                .rating_quartile
+                .unwrap_or_else(default_rating_quartile),
+        }
+    }
+}
+
+/// Hands rating snapshots off to a background thread so the interactive prompt
+/// loop does not wait on the sort/quartile/write/rename of `save_ratings`.
+/// Snapshots are coalesced: if several pile up while the thread is busy, only
+/// the most recent one actually gets written. The channel holds at most four
+/// snapshots; `send` blocks while it is full.
+struct ThreadProxyWriter {
+    sender: Option<SyncSender<Vec<RatedItem>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ThreadProxyWriter {
+    fn spawn(file: PathBuf, format: Format) -> Self {
+        let (sender, receiver) = sync_channel::<Vec<RatedItem>>(4);
+        let handle = thread::spawn(move || {
+            while let Ok(mut items) = receiver.recv() {
+                // Skip straight to the latest snapshot if more arrived while we
+                // were still writing the previous one.
+                while let Ok(newer) = receiver.try_recv() {
+                    items = newer;
+                }
+                save_ratings(&file, &mut items, format);
+            }
+        });
+        Self {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Queue a snapshot to be sorted, quartiled, and persisted off-thread.
+    fn send(&self, items: Vec<RatedItem>) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(items);
+        }
+    }
+
+    /// Block until the latest queued snapshot has been written, then shut the
+    /// thread down. Closing the sender lets the writer drain its backlog before
+    /// its `recv` loop exits, so the final file on disk is always complete.
+    fn flush(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().expect("Ratings writer thread panicked");
+        }
+    }
+}
+
+impl Drop for ThreadProxyWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn run_resort(file: &PathBuf, do_decay: bool, seed: Option<u64>, format: Format) {
+    let mut rng = match seed {
+        Some(seed) => {
+            println!("Using seed {seed} for this session.");
+            StdRng::seed_from_u64(seed)
+        }
+        None => StdRng::from_entropy(),
+    };
+
+    println!("Loading ratings from disk...");
+    let mut items = load_items(file, format);
 
     if do_decay {
         println!("Processing rating decay...");
@@ -93,6 +476,13 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
             let new_rating = decay_deviation(&rating, &GlickoConfig::default());
             item.rating = new_rating.rating;
             item.deviation = new_rating.deviation;
+            append_journal_entry(
+                file,
+                &JournalEntry::Decay {
+                    timestamp: now_timestamp(),
+                    name: item.name.clone(),
+                },
+            );
         }
     }
 
@@ -104,10 +494,9 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
     let rating_deviation_threshold = 65.0;
 
     // Shuffle the items so that they aren't presented in a predictable order.
-    {
-        let mut rng = rand::thread_rng();
-        items.shuffle(&mut rng);
-    }
+    items.shuffle(&mut rng);
+
+    let mut writer = ThreadProxyWriter::spawn(file.clone(), format);
 
     let mut unstabilized = items
         .iter()
@@ -119,7 +508,7 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
         {
             let left;
             let right;
-            if rand::random::<f64>() < 0.25 {
+            if rng.gen::<f64>() < 0.25 {
                 // Most of the time, select the two top deviations.
                 items.sort_unstable_by(|a, b| {
                     a.deviation
@@ -134,7 +523,6 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
                 }
             } else {
                 // A minority of the time, select two random items.
-                let mut rng = rand::thread_rng();
                 let (needed_items, other_items) = (items.partial_shuffle(&mut rng, 2));
                 let (left_part, right_part) = needed_items.split_at_mut(1);
                 left = &mut left_part[0];
@@ -160,6 +548,15 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
                 x if x == &right_name => Outcomes::LOSS,
                 _ => Outcomes::LOSS,
             };
+            append_journal_entry(
+                file,
+                &JournalEntry::Comparison {
+                    timestamp: now_timestamp(),
+                    left: left.name.clone(),
+                    right: right.name.clone(),
+                    outcome: outcome.into(),
+                },
+            );
             let (new_left_player, new_right_player) = glicko(
                 &left_player,
                 &right_player,
@@ -172,8 +569,9 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
             right.deviation = new_right_player.deviation;
         }
 
-        // Save the current ratings.
-        save_ratings(file, &mut items);
+        // Hand the current ratings off to the background writer so the next
+        // question can be asked without waiting on disk I/O.
+        writer.send(items.clone());
 
         // Check if the ratings are now stabilized.
         unstabilized = false;
@@ -184,10 +582,13 @@ fn run_resort(file: &PathBuf, do_decay: bool) {
             }
         }
     }
+
+    // Make sure the last queued snapshot is actually on disk before we report success.
+    writer.flush();
     println!("Ratings are stabilized!");
 }
 
-fn save_ratings(file: &PathBuf, items: &mut Vec<RatedItem>) {
+fn save_ratings(file: &PathBuf, items: &mut Vec<RatedItem>, format: Format) {
     // Sort the items based on the rating.
     items.sort_unstable_by(|a, b| {
         a.rating
@@ -208,18 +609,128 @@ fn save_ratings(file: &PathBuf, items: &mut Vec<RatedItem>) {
     }
 
     let new = file.with_extension("new");
-    let opened = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(&new)
-        .expect(&format!("Failed to open file {}", new.display()));
-    let mut csv_writer = csv::WriterBuilder::new()
-        .has_headers(false)
-        .from_writer(opened);
-    for item in items.iter() {
-        csv_writer.serialize(item).expect("Failed to write record");
+    match format {
+        Format::Csv => write_items_csv(&new, items),
+        Format::Jsonl => write_items_jsonl(&new, items),
+        Format::Binary => write_items_binary(&new, items),
     }
 
     std::fs::rename(new, file).expect("Failed to replace old ratings list with new one");
 }
+
+/// Initialize an item the first time the journal mentions its name, at the
+/// Glicko cold-start rating/deviation the request specifies for Replay. This is
+/// deliberately not `default_rating()`/`default_deviation()`: a name that only
+/// exists via old journal entries (e.g. after the file was edited or trimmed)
+/// needs the wide-uncertainty Glicko baseline, not the `Add`-row convention.
+fn ensure_item(ratings: &mut HashMap<String, RatedItem>, name: &str) {
+    ratings
+        .entry(name.to_string())
+        .or_insert_with(|| RatedItem {
+            name: name.to_string(),
+            rating: 1500.0,
+            deviation: 350.0,
+            rating_quartile: 0,
+        });
+}
+
+fn run_replay(file: &PathBuf, format: Format) {
+    let journal = journal_path(file);
+    println!("Replaying journal from {}...", journal.display());
+    let opened = OpenOptions::new()
+        .read(true)
+        .open(&journal)
+        .expect(&format!(
+            "Failed to open journal file {}",
+            journal.display()
+        ));
+
+    let mut entries: Vec<JournalEntry> = BufReader::new(opened)
+        .lines()
+        .filter_map(|line| {
+            let line = line.expect("Failed to read journal line");
+            if line.trim().is_empty() {
+                None
+            } else {
+                Some(serde_json::from_str(&line).expect("Failed to parse journal entry"))
+            }
+        })
+        .collect();
+
+    entries.sort_by_key(|entry| match entry {
+        JournalEntry::Comparison { timestamp, .. } => *timestamp,
+        JournalEntry::Decay { timestamp, .. } => *timestamp,
+    });
+
+    let mut journaled_names: HashSet<String> = HashSet::new();
+    for entry in &entries {
+        match entry {
+            JournalEntry::Comparison { left, right, .. } => {
+                journaled_names.insert(left.clone());
+                journaled_names.insert(right.clone());
+            }
+            JournalEntry::Decay { name, .. } => {
+                journaled_names.insert(name.clone());
+            }
+        }
+    }
+
+    let mut ratings: HashMap<String, RatedItem> = HashMap::new();
+    for entry in entries {
+        match entry {
+            JournalEntry::Comparison {
+                left,
+                right,
+                outcome,
+                ..
+            } => {
+                ensure_item(&mut ratings, &left);
+                ensure_item(&mut ratings, &right);
+                let left_player = GlickoRating {
+                    rating: ratings[&left].rating,
+                    deviation: ratings[&left].deviation,
+                };
+                let right_player = GlickoRating {
+                    rating: ratings[&right].rating,
+                    deviation: ratings[&right].deviation,
+                };
+                let (new_left, new_right) = glicko(
+                    &left_player,
+                    &right_player,
+                    &outcome.into(),
+                    &GlickoConfig::default(),
+                );
+                let left_item = ratings.get_mut(&left).unwrap();
+                left_item.rating = new_left.rating;
+                left_item.deviation = new_left.deviation;
+                let right_item = ratings.get_mut(&right).unwrap();
+                right_item.rating = new_right.rating;
+                right_item.deviation = new_right.deviation;
+            }
+            JournalEntry::Decay { name, .. } => {
+                ensure_item(&mut ratings, &name);
+                let item = ratings.get_mut(&name).unwrap();
+                let rating = GlickoRating {
+                    rating: item.rating,
+                    deviation: item.deviation,
+                };
+                let new_rating = decay_deviation(&rating, &GlickoConfig::default());
+                item.rating = new_rating.rating;
+                item.deviation = new_rating.deviation;
+            }
+        }
+    }
+
+    // Preserve items that have zero journal entries at all (e.g. just `Add`ed,
+    // never yet `Resort`ed) instead of dropping them; everything else is
+    // rebuilt purely from the journal, cold-starting at 1500/350.
+    for item in load_items(file, format) {
+        if !journaled_names.contains(&item.name) {
+            ratings.insert(item.name.clone(), item);
+        }
+    }
+
+    let mut items: Vec<RatedItem> = ratings.into_values().collect();
+    println!("Rebuilt {} items from the journal.", items.len());
+    save_ratings(file, &mut items, format);
+}